@@ -0,0 +1,6 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Base, low-level types and traits shared across the Linera protocol.
+
+pub mod tracing_opentelemetry;
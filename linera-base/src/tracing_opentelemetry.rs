@@ -0,0 +1,1099 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracing and OpenTelemetry integration for Linera services.
+//!
+//! This module wires [`tracing`] spans into two export backends:
+//!
+//! * a Chrome trace JSON file, always available, mainly used for local
+//!   profiling (`init_with_chrome_trace_exporter`);
+//! * an OpenTelemetry OTLP pipeline, enabled via the `opentelemetry`
+//!   feature, used to ship traces to a collector in production
+//!   (`build_opentelemetry_layer_with_test_exporter` and friends).
+//!
+//! A span (or an individual event) can opt out of OpenTelemetry export
+//! without affecting the Chrome trace by setting the `opentelemetry.skip`
+//! field, e.g. `#[instrument(fields(opentelemetry.skip = true))]`. The
+//! Chrome trace exporter always records every span: it is a local
+//! debugging aid, not a cost center the way a collector ingestion
+//! pipeline is.
+//!
+//! Traces that cross a validator RPC boundary are continued rather than
+//! restarted: see [`inject_trace_context`] and [`extract_trace_context`].
+//!
+//! The filter each init function installs can be changed while the
+//! process is running, without a restart, via the [`FilterHandle`] it
+//! returns alongside its guard: an admin endpoint can call
+//! [`FilterHandle::set_directives`] to raise verbosity on a single
+//! validator while debugging an incident, then revert it afterwards.
+//!
+//! [`init_with_otlp_exporter`] gets the same live-reload treatment via a
+//! reloadable [`tracing_subscriber::filter::Targets`] instead of an
+//! `EnvFilter`, since per-target directives are what operators actually
+//! reach for on a running validator; the in-memory test builder below does
+//! not need reload support at all.
+//!
+//! [`init_with_otlp_exporter`] also selects which OTLP wire transport to
+//! use via [`OtlpTransport`] (gRPC, HTTP/protobuf, or HTTP/JSON): the skip
+//! fast-path, sampling, and span-kind/status mapping above all sit in the
+//! `tracing` layer, above the exporter, so they behave identically no
+//! matter which transport is picked.
+//!
+//! The OpenTelemetry path also supports [`SamplingConfig`], a head-based
+//! sampler: the decision to keep or drop a trace is made once, at the
+//! OpenTelemetry SDK layer via [`PerTargetRatioSampler`], using the real
+//! trace id, before the trace's root span is even recorded, and every
+//! descendant span inherits that outcome. This keeps export volume
+//! manageable on a high-traffic validator without ever splitting a trace
+//! between sampled and dropped spans. `opentelemetry.skip` remains a
+//! separate, absolute drop applied by a `tracing` [`Filter`] instead (see
+//! [`OpenTelemetrySkipFilter`]), and it has no effect on the
+//! always-exports-everything Chrome trace path.
+//!
+//! Finished spans are batched (see [`BatchExportConfig`]) rather than
+//! exported one at a time. [`group_spans_by_resource_and_scope`] is a
+//! standalone helper for partitioning a batch by resource and scope; it
+//! is not wired into either builder below, since `opentelemetry_sdk`'s own
+//! OTLP encoder already groups a batch that way while serializing it.
+//!
+//! Spans that should render as a distinct node in a trace waterfall (e.g.
+//! the client and server sides of an RPC) can set the `otel.kind` field to
+//! one of `"server"`, `"client"`, `"producer"`, or `"consumer"`; it
+//! defaults to `"internal"` when absent or unrecognized, e.g.
+//! `#[instrument(fields(otel.kind = "server"))]`. Likewise, `otel.status_code`
+//! (`"ok"`/`"error"`, case-insensitively) and `otel.status_message` set the
+//! exported span's status, which is what makes failed spans render red in
+//! most trace UIs. [`OtelKindAndStatusLayer`] reads these fields and
+//! applies them directly, the same way [`StripSelfSkippedOtelData`]
+//! applies `opentelemetry.skip`.
+
+use std::io::Write;
+
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::trace::{TraceContextExt as _, TracerProvider as _};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::{
+    field::Visit,
+    layer::{Context, Filter, Layer, SubscriberExt as _},
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt as _,
+    EnvFilter,
+};
+
+/// Error returned by [`FilterHandle::set_directives`].
+#[derive(Debug, thiserror::Error)]
+pub enum FilterReloadError {
+    /// The supplied directive string could not be parsed.
+    #[error("invalid filter directives: {0}")]
+    Parse(String),
+    /// The new filter was built successfully but could not be swapped in,
+    /// typically because the subscriber it belongs to has already been
+    /// dropped.
+    #[error("failed to apply new filter: {0}")]
+    Reload(#[from] reload::Error),
+}
+
+/// A handle that lets operators change a running filter's directives
+/// without restarting the process.
+///
+/// Returned by [`init_with_chrome_trace_exporter`] and
+/// [`init_with_otlp_exporter`] alongside their usual guard. Cheap to clone
+/// and safe to hand to an admin endpoint.
+pub struct FilterHandle<F, S> {
+    inner: reload::Handle<F, S>,
+}
+
+impl<F, S> Clone for FilterHandle<F, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F, S> FilterHandle<F, S>
+where
+    F: std::str::FromStr + Send + Sync + 'static,
+    F::Err: std::fmt::Display,
+    S: 'static,
+{
+    /// Parses `directives` and atomically swaps them in as the new active
+    /// filter, e.g. `"linera_core=debug,linera_chain=trace"`. The previous
+    /// filter stays in effect if parsing fails, so a malformed directive
+    /// string can never knock out logging on a live validator.
+    pub fn set_directives(&self, directives: &str) -> Result<(), FilterReloadError> {
+        let new_filter = directives
+            .parse::<F>()
+            .map_err(|error| FilterReloadError::Parse(error.to_string()))?;
+        self.inner.reload(new_filter)?;
+        Ok(())
+    }
+}
+
+/// Field name spans and events use to opt out of OpenTelemetry export.
+///
+/// The Chrome trace exporter ignores this field: it is only consulted by
+/// the OpenTelemetry layer.
+const SKIP_FIELD: &str = "opentelemetry.skip";
+
+/// Guard returned by [`init_with_chrome_trace_exporter`].
+///
+/// Dropping it flushes and closes the Chrome trace file; callers should
+/// keep it alive for as long as they want spans recorded, typically for
+/// the lifetime of the process or test.
+pub struct ChromeTraceGuard {
+    _flush_guard: FlushGuard,
+}
+
+/// Type of the filter handle returned by [`init_with_chrome_trace_exporter`].
+pub type ChromeFilterHandle = FilterHandle<EnvFilter, tracing_subscriber::Registry>;
+
+/// Initializes a global [`tracing`] subscriber that records every span and
+/// event into a Chrome trace JSON stream written to `writer`.
+///
+/// `service_name` is recorded as the process name in the resulting trace.
+/// Returns a guard that must be held until tracing should stop (dropping
+/// it flushes the writer), and a [`FilterHandle`] that can change the
+/// active `EnvFilter` directives at any point afterwards.
+pub fn init_with_chrome_trace_exporter<W>(
+    service_name: &str,
+    writer: W,
+) -> (ChromeTraceGuard, ChromeFilterHandle)
+where
+    W: Write + Send + 'static,
+{
+    let (chrome_layer, flush_guard) = ChromeLayerBuilder::new()
+        .name_fn(Box::new(|event_or_span| {
+            event_or_span
+                .metadata()
+                .name()
+                .to_string()
+        }))
+        .writer(writer)
+        .build();
+
+    let initial_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, reload_handle) = reload::Layer::new(initial_filter);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(chrome_layer.with_process_name(service_name.to_string()))
+        .try_init()
+        .ok();
+
+    (
+        ChromeTraceGuard {
+            _flush_guard: flush_guard,
+        },
+        FilterHandle {
+            inner: reload_handle,
+        },
+    )
+}
+
+/// A [`Visit`] implementation that records whether the `opentelemetry.skip`
+/// field was set to `true` on a span or event.
+#[derive(Default)]
+struct SkipVisitor {
+    skip: bool,
+}
+
+impl Visit for SkipVisitor {
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        if field.name() == SKIP_FIELD {
+            self.skip = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Marker stored in a span's extensions recording whether it (or an
+/// ancestor) was created with `opentelemetry.skip = true`.
+#[derive(Clone, Copy)]
+struct Skipped(bool);
+
+/// A [`Visit`] implementation that records the well-known `otel.kind`,
+/// `otel.status_code`, and `otel.status_message` fields off a span.
+#[derive(Default)]
+struct KindAndStatusVisitor {
+    kind: Option<String>,
+    status_code: Option<String>,
+    status_message: Option<String>,
+}
+
+impl Visit for KindAndStatusVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match field.name() {
+            "otel.kind" => self.kind = Some(value.to_string()),
+            "otel.status_code" => self.status_code = Some(value.to_string()),
+            "otel.status_message" => self.status_message = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Configures what fraction of whole traces the OpenTelemetry pipeline
+/// keeps, with optional overrides for specific span names.
+///
+/// The most specific (longest) matching override wins; if none match,
+/// `default_ratio` applies. Matching is against the new span's *name*
+/// rather than its `tracing` target: the decision is made by a
+/// [`PerTargetRatioSampler`] at the point the OpenTelemetry SDK starts the
+/// span, which is the only point with access to the real, consistent
+/// trace id (see its docs for why), and at that point `tracing`'s target
+/// is no longer available — the span's name is.
+#[derive(Debug, Clone)]
+pub struct SamplingConfig {
+    default_ratio: f64,
+    name_ratios: Vec<(String, f64)>,
+}
+
+impl SamplingConfig {
+    /// Samples `default_ratio` of all traces (e.g. `0.01` keeps about 1%),
+    /// with no per-name overrides yet.
+    pub fn new(default_ratio: f64) -> Self {
+        Self {
+            default_ratio: default_ratio.clamp(0.0, 1.0),
+            name_ratios: Vec::new(),
+        }
+    }
+
+    /// Always keeps every trace; equivalent to `SamplingConfig::new(1.0)`.
+    pub fn always_on() -> Self {
+        Self::new(1.0)
+    }
+
+    /// Overrides the ratio for root spans named `name` (and, by prefix, its
+    /// sub-names, e.g. `"linera_core::client"` also matches
+    /// `"linera_core::client::query"`), e.g.
+    /// `.with_target_ratio("linera_core::client", 1.0)` to always keep a
+    /// noisy-but-important subsystem while sampling everything else down.
+    pub fn with_target_ratio(mut self, name: impl Into<String>, ratio: f64) -> Self {
+        self.name_ratios.push((name.into(), ratio.clamp(0.0, 1.0)));
+        // Longest (most specific) name first, so the first match found by
+        // a linear scan is always the most specific one.
+        self.name_ratios
+            .sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        self
+    }
+
+    fn ratio_for(&self, name: &str) -> f64 {
+        self.name_ratios
+            .iter()
+            .find(|(prefix, _)| name == prefix || name.starts_with(&format!("{prefix}::")))
+            .map_or(self.default_ratio, |(_, ratio)| *ratio)
+    }
+}
+
+impl Default for SamplingConfig {
+    /// Keeps every trace, matching the export behavior before sampling
+    /// existed.
+    fn default() -> Self {
+        Self::always_on()
+    }
+}
+
+/// Deterministically decides whether to keep a trace given a 64-bit
+/// identifier and a target ratio, the same way OpenTelemetry's
+/// `TraceIdRatioBased` sampler does: compare the identifier against a
+/// threshold scaled by the ratio, so the same identifier always yields the
+/// same decision (no coordination between services required) and, in
+/// aggregate across many identifiers, keeps approximately `ratio` of them.
+fn sampling_decision(key: u64, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    if ratio <= 0.0 {
+        return false;
+    }
+    let threshold = (ratio * u64::MAX as f64) as u64;
+    key <= threshold
+}
+
+/// Low 64 bits of a 128-bit OTel trace id, used as the hash key for the
+/// ratio comparison in [`PerTargetRatioSampler`] — the same slice
+/// `opentelemetry_sdk`'s own `TraceIdRatioBased` sampler uses.
+fn trace_id_low_bits(trace_id: opentelemetry::trace::TraceId) -> u64 {
+    let bytes = trace_id.to_bytes();
+    u64::from_be_bytes(bytes[8..16].try_into().expect("A trace id is 16 bytes"))
+}
+
+/// A head-based [`opentelemetry_sdk::trace::ShouldSample`] that makes its
+/// ratio decision once, using the real (distributed) trace id, and lets
+/// every other span in the trace inherit it.
+///
+/// This runs inside the OpenTelemetry SDK's own span-start path, before
+/// any `SpanData` is built — unlike a `tracing` [`Filter`], which can only
+/// see a span *after* admitting it, this can veto a trace's root before it
+/// is ever recorded. It also receives the actual trace id: a freshly
+/// generated 128-bit id for a locally-rooted trace, or the id inherited
+/// from an upstream, already-decided trace for one that crossed an RPC
+/// boundary (see [`extract_trace_context`]) — never a process-local
+/// `tracing::span::Id`, which is a small slab index that would bias the
+/// ratio comparison and differ from service to service for the same
+/// trace.
+#[derive(Debug, Clone)]
+struct PerTargetRatioSampler {
+    sampling: SamplingConfig,
+}
+
+impl opentelemetry_sdk::trace::ShouldSample for PerTargetRatioSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&opentelemetry::Context>,
+        trace_id: opentelemetry::trace::TraceId,
+        name: &str,
+        _span_kind: &opentelemetry::trace::SpanKind,
+        _attributes: &[opentelemetry::KeyValue],
+        _links: &[opentelemetry::trace::Link],
+    ) -> opentelemetry_sdk::trace::SamplingResult {
+        use opentelemetry::trace::TraceContextExt as _;
+        use opentelemetry_sdk::trace::SamplingDecision;
+
+        // Honor an already-decided parent instead of rolling the dice
+        // again: head-based sampling decides once, at the true root, and
+        // every other span (local child or downstream service) must
+        // follow that single decision rather than compete with it.
+        if let Some(parent_span_context) = parent_context.map(|cx| cx.span().span_context()) {
+            if parent_span_context.is_valid() {
+                let decision = if parent_span_context.is_sampled() {
+                    SamplingDecision::RecordAndSample
+                } else {
+                    SamplingDecision::Drop
+                };
+                return opentelemetry_sdk::trace::SamplingResult {
+                    decision,
+                    attributes: Vec::new(),
+                    trace_state: parent_span_context.trace_state().clone(),
+                };
+            }
+        }
+
+        let ratio = self.sampling.ratio_for(name);
+        let decision = if sampling_decision(trace_id_low_bits(trace_id), ratio) {
+            SamplingDecision::RecordAndSample
+        } else {
+            SamplingDecision::Drop
+        };
+
+        opentelemetry_sdk::trace::SamplingResult {
+            decision,
+            attributes: Vec::new(),
+            trace_state: opentelemetry::trace::TraceState::default(),
+        }
+    }
+}
+
+/// A [`Filter`] that excludes spans and events marked `opentelemetry.skip`
+/// from whatever layer it is attached to (in particular the OpenTelemetry
+/// layer; the Chrome trace layer never applies this filter, so it keeps
+/// seeing every span regardless).
+///
+/// Skip is inherited: an event nested under a skipped span is skipped too,
+/// even if the event itself does not set the field. Sampling is a
+/// separate concern, handled by [`PerTargetRatioSampler`] at the
+/// OpenTelemetry SDK layer rather than here: see its docs for why a
+/// `Filter` cannot correctly veto a trace's root span.
+struct OpenTelemetrySkipFilter;
+
+impl<S> Filter<S> for OpenTelemetrySkipFilter
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>, ctx: &Context<'_, S>) -> bool {
+        if let Some(span) = ctx.lookup_current() {
+            for span in span.scope().from_root() {
+                if let Some(Skipped(true)) = span.extensions().get::<Skipped>().copied() {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: Context<'_, S>) {
+        let mut visitor = SkipVisitor::default();
+        attrs.record(&mut visitor);
+
+        let inherited_skip = ctx
+            .span(id)
+            .and_then(|span| span.parent())
+            .map(|parent| {
+                parent
+                    .extensions()
+                    .get::<Skipped>()
+                    .map(|Skipped(skip)| *skip)
+                    .unwrap_or(false)
+            })
+            .unwrap_or(false);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut()
+                .insert(Skipped(visitor.skip || inherited_skip));
+        }
+    }
+}
+
+/// A plain [`Layer`] — not a [`Filter`] — that drops the OpenTelemetry
+/// data `tracing_opentelemetry`'s own layer just attached to a span that
+/// set `opentelemetry.skip = true` on *itself*.
+///
+/// [`OpenTelemetrySkipFilter::enabled`] cannot do this: a `Filter`'s
+/// `enabled` is consulted *before* `on_new_span`, with only the span's
+/// metadata and its *parent's* context, so it can never see a new span's
+/// own field values — the same ordering problem [`PerTargetRatioSampler`]
+/// works around for sampling. Descendant spans and events are already
+/// handled correctly by `OpenTelemetrySkipFilter` (by the time they are
+/// checked, their skipped ancestor's `Skipped` marker already exists); it
+/// is only a self-skipping span's own exported record that slips through.
+///
+/// This layer must be registered *after* the OpenTelemetry layer (see
+/// both builder functions below), so that by the time its `on_new_span`
+/// runs, `tracing_opentelemetry` has already attached the `OtelData` this
+/// removes. Once removed, `tracing_opentelemetry` has nothing to export
+/// when the span closes.
+struct StripSelfSkippedOtelData;
+
+impl<S> Layer<S> for StripSelfSkippedOtelData
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, _attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if matches!(extensions.get::<Skipped>(), Some(Skipped(true))) {
+            extensions.remove::<tracing_opentelemetry::OtelData>();
+        }
+    }
+}
+
+/// A plain [`Layer`] that reads the well-known `otel.kind`,
+/// `otel.status_code`, and `otel.status_message` fields off a new span and
+/// applies them directly to the `OtelData` `tracing_opentelemetry`'s own
+/// layer just attached, the same way [`StripSelfSkippedOtelData`] applies
+/// `opentelemetry.skip` rather than relying on `tracing_opentelemetry` to
+/// special-case these field names itself.
+///
+/// `otel.kind` selects one of `"server"`, `"client"`, `"producer"`, or
+/// `"consumer"` (case-insensitively), defaulting to
+/// [`opentelemetry::trace::SpanKind::Internal`] when absent or
+/// unrecognized. `otel.status_code` (`"ok"`/`"error"`, case-insensitively)
+/// sets the exported span's status, carrying `otel.status_message` along
+/// as the error message.
+///
+/// Must be registered *after* the OpenTelemetry layer (see both builder
+/// functions below), for the same reason [`StripSelfSkippedOtelData`]
+/// must be: only by then has `tracing_opentelemetry` attached the
+/// `OtelData` this mutates.
+struct OtelKindAndStatusLayer;
+
+impl<S> Layer<S> for OtelKindAndStatusLayer
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, ctx: Context<'_, S>) {
+        let mut visitor = KindAndStatusVisitor::default();
+        attrs.record(&mut visitor);
+
+        if visitor.kind.is_none() && visitor.status_code.is_none() && visitor.status_message.is_none() {
+            return;
+        }
+
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(otel_data) = extensions.get_mut::<tracing_opentelemetry::OtelData>() else {
+            return;
+        };
+
+        if let Some(kind) = visitor.kind {
+            otel_data.builder.span_kind = Some(match kind.to_ascii_lowercase().as_str() {
+                "server" => opentelemetry::trace::SpanKind::Server,
+                "client" => opentelemetry::trace::SpanKind::Client,
+                "producer" => opentelemetry::trace::SpanKind::Producer,
+                "consumer" => opentelemetry::trace::SpanKind::Consumer,
+                _ => opentelemetry::trace::SpanKind::Internal,
+            });
+        }
+
+        if let Some(status_code) = visitor.status_code {
+            otel_data.builder.status = match status_code.to_ascii_lowercase().as_str() {
+                "error" => opentelemetry::trace::Status::error(visitor.status_message.unwrap_or_default()),
+                "ok" => opentelemetry::trace::Status::Ok,
+                _ => opentelemetry::trace::Status::Unset,
+            };
+        }
+    }
+}
+
+/// Configures how finished spans are batched before being handed to an
+/// OTLP exporter: how many accumulate before a batch is flushed early, and
+/// how long a partial batch is allowed to sit before it is flushed anyway.
+///
+/// Larger batches and longer delays favor throughput (fewer, bigger
+/// requests to the collector); smaller ones favor latency (traces show up
+/// sooner). Mirrors the defaults of
+/// [`opentelemetry_sdk::trace::BatchConfigBuilder`], so leaving this at
+/// `default()` behaves the same as the unconfigured batch processor did.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchExportConfig {
+    /// Spans accumulated past this count trigger an immediate export
+    /// rather than waiting for `scheduled_delay`.
+    pub max_export_batch_size: usize,
+    /// Longest a non-empty, non-full batch waits before being exported.
+    pub scheduled_delay: std::time::Duration,
+}
+
+impl Default for BatchExportConfig {
+    fn default() -> Self {
+        Self {
+            max_export_batch_size: 512,
+            scheduled_delay: std::time::Duration::from_secs(5),
+        }
+    }
+}
+
+/// One OTLP `ResourceSpans`/`ScopeSpans` worth of finished spans: every
+/// span in `spans` shares both `resource` and `scope`, so a payload
+/// builder can emit a single `ResourceSpans`/`ScopeSpans` pair for all of
+/// them instead of repeating the resource and scope once per span.
+///
+/// Not currently wired into either builder function below:
+/// `opentelemetry_sdk`'s own OTLP encoder already groups a batch by
+/// resource and scope while serializing it (see
+/// `test_http_json_transport_groups_spans_sharing_a_scope_into_one_scope_spans_entry`,
+/// which asserts this directly against the wire format), so re-grouping a
+/// batch here before handing it to that encoder would
+/// only add a clone pass, not change what goes on the wire. This remains a
+/// standalone utility for a caller writing its own
+/// [`opentelemetry_sdk::export::trace::SpanExporter`] that needs the
+/// partitioning directly (e.g. one emitting a custom wire format that
+/// does not already group internally).
+pub struct ResourceScopeBatch<'a> {
+    /// The resource (e.g. `service.name`) spans in this group were
+    /// recorded under. A `TracerProvider` has exactly one resource, so
+    /// this is the same value in every group produced by one
+    /// `group_spans_by_resource_and_scope` call; it is still carried
+    /// per-group so a caller can hand each group to a payload builder
+    /// without threading a separate resource argument alongside it.
+    pub resource: opentelemetry_sdk::Resource,
+    /// The instrumentation scope (tracer name/version) all spans in this
+    /// group were recorded through.
+    pub scope: opentelemetry::InstrumentationScope,
+    /// The spans themselves, in the order they appeared in the input
+    /// batch.
+    pub spans: Vec<&'a opentelemetry_sdk::export::trace::SpanData>,
+}
+
+/// Partitions a finished-span batch by instrumentation scope, pairing each
+/// group with `resource`, the (single, process-wide) resource the caller
+/// exports under.
+///
+/// Grouping is stable: spans keep their relative order within a group, and
+/// groups are returned in first-seen order, which keeps exporter output
+/// deterministic for a given input batch.
+pub fn group_spans_by_resource_and_scope<'a>(
+    resource: &opentelemetry_sdk::Resource,
+    spans: &'a [opentelemetry_sdk::export::trace::SpanData],
+) -> Vec<ResourceScopeBatch<'a>> {
+    let mut scope_order: Vec<opentelemetry::InstrumentationScope> = Vec::new();
+    let mut by_scope: std::collections::HashMap<
+        opentelemetry::InstrumentationScope,
+        Vec<&'a opentelemetry_sdk::export::trace::SpanData>,
+    > = std::collections::HashMap::new();
+
+    for span in spans {
+        let scope = span.instrumentation_scope.clone();
+        by_scope
+            .entry(scope.clone())
+            .or_insert_with(|| {
+                scope_order.push(scope.clone());
+                Vec::new()
+            })
+            .push(span);
+    }
+
+    scope_order
+        .into_iter()
+        .map(|scope| ResourceScopeBatch {
+            resource: resource.clone(),
+            spans: by_scope.remove(&scope).unwrap_or_default(),
+            scope,
+        })
+        .collect()
+}
+
+/// Builds an [`opentelemetry`] tracing layer backed by an in-memory
+/// exporter, for use in tests that want to assert which spans were
+/// exported without standing up a real collector.
+///
+/// `sampling` controls what fraction of traces are kept; pass
+/// [`SamplingConfig::always_on`] to export everything, which is what every
+/// caller did before sampling was configurable. `batching` controls how
+/// many finished spans accumulate, and for how long, before a batch is
+/// flushed to the exporter.
+///
+/// Returns the layer (to be added to a [`tracing_subscriber::Registry`]),
+/// the exporter (to inspect finished spans after the subscriber is
+/// dropped), and the tracer provider (which must be kept alive, and
+/// dropped to force a flush, for the duration of the test).
+#[cfg(feature = "opentelemetry")]
+pub fn build_opentelemetry_layer_with_test_exporter<S>(
+    service_name: &str,
+    sampling: SamplingConfig,
+    batching: BatchExportConfig,
+) -> (
+    impl Layer<S>,
+    opentelemetry_sdk::testing::trace::InMemorySpanExporter,
+    opentelemetry_sdk::trace::TracerProvider,
+)
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_sdk::testing::trace::InMemorySpanExporterBuilder::new().build();
+    let resource = opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]);
+
+    let batch_config = opentelemetry_sdk::trace::BatchConfigBuilder::default()
+        .with_max_export_batch_size(batching.max_export_batch_size)
+        .with_scheduled_delay(batching.scheduled_delay)
+        .build();
+
+    let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_span_processor(
+            opentelemetry_sdk::trace::BatchSpanProcessor::builder(
+                exporter.clone(),
+                opentelemetry_sdk::runtime::Tokio,
+            )
+            .with_batch_config(batch_config)
+            .build(),
+        )
+        .with_sampler(PerTargetRatioSampler { sampling })
+        .with_resource(resource)
+        .build();
+
+    let tracer = tracer_provider.tracer(service_name.to_string());
+    let otel_layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(OpenTelemetrySkipFilter);
+
+    (
+        (otel_layer, OtelKindAndStatusLayer, StripSelfSkippedOtelData),
+        exporter,
+        tracer_provider,
+    )
+}
+
+/// Selects which OTLP wire transport [`init_with_otlp_exporter`] ships
+/// spans over.
+///
+/// Most collectors accept gRPC, but some only accept OTLP over HTTP, and
+/// HTTP/JSON in particular is easy to route through ordinary HTTP
+/// infrastructure (proxies, load balancers) and to inspect by hand while
+/// debugging a pipeline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtlpTransport {
+    /// OTLP over gRPC (via `tonic`). The default: most collectors expect
+    /// this.
+    #[default]
+    Grpc,
+    /// OTLP over HTTP with protobuf-encoded bodies.
+    HttpBinary,
+    /// OTLP over HTTP with JSON-encoded bodies.
+    HttpJson,
+}
+
+/// Configures the OTLP exporter [`init_with_otlp_exporter`] builds.
+///
+/// `endpoint`, `headers`, and `timeout` fall back to the standard
+/// `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_EXPORTER_OTLP_HEADERS`, and
+/// `OTEL_EXPORTER_OTLP_TIMEOUT` environment variables (read by
+/// `opentelemetry-otlp` itself) when left at their defaults, so a
+/// deployment can be pointed at a different collector without a code
+/// change or recompile.
+#[derive(Debug, Clone, Default)]
+pub struct OtlpExporterConfig {
+    /// Which wire transport and encoding to use.
+    pub transport: OtlpTransport,
+    /// Collector endpoint. `None` defers entirely to the environment
+    /// variable and transport-specific default (`http://localhost:4317`
+    /// for gRPC, `http://localhost:4318/v1/traces` for HTTP).
+    pub endpoint: Option<String>,
+    /// Extra headers sent with every export request (e.g. an auth token
+    /// for a multi-tenant collector), merged on top of any set via
+    /// `OTEL_EXPORTER_OTLP_HEADERS`. Only the HTTP transports send these;
+    /// with [`OtlpTransport::Grpc`] this field is ignored.
+    pub headers: std::collections::HashMap<String, String>,
+    /// Per-export request timeout.
+    pub timeout: std::time::Duration,
+}
+
+/// Error returned by [`init_with_otlp_exporter`] when the exporter or
+/// pipeline could not be built, e.g. an unparsable endpoint URL.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to initialize the OTLP exporter: {0}")]
+pub struct OtlpInitError(#[from] opentelemetry::trace::TraceError);
+
+/// Guard returned by [`init_with_otlp_exporter`].
+///
+/// Dropping it shuts down the tracer provider, flushing any spans still
+/// sitting in the batch exporter.
+pub struct OtlpGuard {
+    tracer_provider: opentelemetry_sdk::trace::TracerProvider,
+}
+
+impl Drop for OtlpGuard {
+    fn drop(&mut self) {
+        if let Err(error) = self.tracer_provider.shutdown() {
+            tracing::warn!(?error, "Failed to cleanly shut down the OTLP tracer provider");
+        }
+    }
+}
+
+/// Type of the filter handle returned by [`init_with_otlp_exporter`].
+pub type OtlpFilterHandle =
+    FilterHandle<tracing_subscriber::filter::Targets, tracing_subscriber::Registry>;
+
+/// Initializes a global [`tracing`] subscriber that ships spans to a real
+/// OTLP collector over `config.transport`, applying `sampling` and
+/// `batching` exactly like [`build_opentelemetry_layer_with_test_exporter`]
+/// does for its in-memory exporter: the `opentelemetry.skip` fast path,
+/// sampling decisions, and `otel.kind`/`otel.status_code` mapping all
+/// behave identically regardless of which transport is selected, since
+/// they are applied above the exporter, not inside it.
+///
+/// The reloadable filter starts at `info` for every target, matching
+/// [`init_with_chrome_trace_exporter`]'s `EnvFilter` fallback, until a
+/// caller narrows or widens it via the returned [`FilterHandle`].
+///
+/// Returns a guard that must be held until tracing should stop (dropping
+/// it flushes and shuts down the pipeline), and a [`FilterHandle`] that can
+/// change the per-target `Targets` filter's directives at any point
+/// afterwards, the same way [`init_with_chrome_trace_exporter`]'s handle
+/// does for its `EnvFilter`.
+#[cfg(feature = "opentelemetry")]
+pub fn init_with_otlp_exporter(
+    service_name: &str,
+    config: OtlpExporterConfig,
+    sampling: SamplingConfig,
+    batching: BatchExportConfig,
+) -> Result<(OtlpGuard, OtlpFilterHandle), OtlpInitError> {
+    use opentelemetry_otlp::WithExportConfig as _;
+
+    let span_exporter = match config.transport {
+        OtlpTransport::Grpc => {
+            let mut builder = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_timeout(config.timeout);
+            if let Some(endpoint) = &config.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            opentelemetry_otlp::SpanExporterBuilder::Tonic(builder)
+        }
+        OtlpTransport::HttpBinary | OtlpTransport::HttpJson => {
+            let protocol = if config.transport == OtlpTransport::HttpJson {
+                opentelemetry_otlp::Protocol::HttpJson
+            } else {
+                opentelemetry_otlp::Protocol::HttpBinary
+            };
+            let mut builder = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_protocol(protocol)
+                .with_timeout(config.timeout)
+                .with_headers(config.headers.clone());
+            if let Some(endpoint) = &config.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            opentelemetry_otlp::SpanExporterBuilder::Http(builder)
+        }
+    };
+
+    let batch_config = opentelemetry_sdk::trace::BatchConfigBuilder::default()
+        .with_max_export_batch_size(batching.max_export_batch_size)
+        .with_scheduled_delay(batching.scheduled_delay)
+        .build();
+
+    let tracer_provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(span_exporter)
+        .with_batch_config(batch_config)
+        .with_trace_config(
+            opentelemetry_sdk::trace::Config::default()
+                .with_sampler(PerTargetRatioSampler { sampling })
+                .with_resource(opentelemetry_sdk::Resource::new(vec![
+                    opentelemetry::KeyValue::new("service.name", service_name.to_string()),
+                ])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    let tracer = tracer_provider.tracer(service_name.to_string());
+    let otel_layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(OpenTelemetrySkipFilter);
+
+    // `Targets` with no directives and no default matches nothing, which
+    // as a global layer would silence every span and event, not just leave
+    // them unfiltered; seed it with the same `info` default the Chrome
+    // trace path falls back to so the pipeline exports something out of
+    // the box, with per-target overrides reachable afterwards via the
+    // returned `FilterHandle`.
+    let initial_targets = tracing_subscriber::filter::Targets::new()
+        .with_default(tracing_subscriber::filter::LevelFilter::INFO);
+    let (reloadable_targets, reload_handle) = reload::Layer::new(initial_targets);
+
+    tracing_subscriber::registry()
+        .with(otel_layer)
+        .with(OtelKindAndStatusLayer)
+        .with(StripSelfSkippedOtelData)
+        .with(reloadable_targets)
+        .try_init()
+        .ok();
+
+    Ok((
+        OtlpGuard { tracer_provider },
+        FilterHandle {
+            inner: reload_handle,
+        },
+    ))
+}
+
+/// Identifies which propagation format [`inject_trace_context`]
+/// and [`extract_trace_context`] use on the wire.
+///
+/// Different deployments front the Linera network with different
+/// collectors, and not all of them speak plain W3C Trace Context, so the
+/// propagator is selectable rather than hardcoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TracePropagator {
+    /// [W3C Trace Context](https://www.w3.org/TR/trace-context/): the
+    /// `traceparent`/`tracestate` headers. This is the default, and the
+    /// only format implemented directly in this module; the others are
+    /// provided by delegating to `opentelemetry`'s own propagators.
+    #[default]
+    W3c,
+    /// AWS X-Ray's `X-Amzn-Trace-Id` format.
+    XRay,
+    /// Datadog's `x-datadog-trace-id`/`x-datadog-parent-id` headers.
+    Datadog,
+}
+
+/// Version byte of the W3C `traceparent` header this module produces and
+/// accepts. Bumping this requires extending [`extract_trace_context`] to
+/// keep parsing older versions, per the W3C spec's forward-compatibility
+/// rules.
+const W3C_TRACEPARENT_VERSION: u8 = 0;
+
+/// Serializes the span context carried by `cx` into `carrier` (e.g.
+/// outbound RPC metadata) using `propagator`, so that the receiving side
+/// can continue the same distributed trace.
+///
+/// With [`TracePropagator::W3c`], this sets `traceparent` (and
+/// `tracestate`, if any) to
+/// `00-{32-hex trace_id}-{16-hex span_id}-{2-hex flags}`, per the W3C
+/// Trace Context specification. A no-op if `cx` has no span or its span
+/// context is not valid (e.g. the empty default context); a valid but
+/// unsampled context is still injected, with the sampled flag cleared.
+#[cfg(feature = "opentelemetry")]
+pub fn inject_trace_context(
+    cx: &opentelemetry::Context,
+    propagator: TracePropagator,
+    carrier: &mut impl Extend<(String, String)>,
+) {
+    let span_context = cx.span().span_context().clone();
+    if !span_context.is_valid() {
+        return;
+    }
+
+    match propagator {
+        TracePropagator::W3c => {
+            let flags = if span_context.is_sampled() { 1u8 } else { 0u8 };
+            let traceparent = format!(
+                "{:02x}-{}-{}-{:02x}",
+                W3C_TRACEPARENT_VERSION,
+                span_context.trace_id(),
+                span_context.span_id(),
+                flags
+            );
+            carrier.extend([("traceparent".to_string(), traceparent)]);
+
+            let tracestate = span_context.trace_state().header();
+            if !tracestate.is_empty() {
+                carrier.extend([("tracestate".to_string(), tracestate)]);
+            }
+        }
+        TracePropagator::XRay => {
+            opentelemetry_aws_like_inject(&span_context, carrier, PropagatorKind::XRay)
+        }
+        TracePropagator::Datadog => {
+            opentelemetry_aws_like_inject(&span_context, carrier, PropagatorKind::Datadog)
+        }
+    }
+}
+
+/// Rebuilds a remote parent [`opentelemetry::Context`] from a carrier
+/// (e.g. inbound RPC metadata) previously populated by
+/// [`inject_trace_context`], so the receiving side's span becomes a child
+/// of the sender's span rather than the root of a new, disconnected trace.
+///
+/// Returns [`opentelemetry::Context::new`] (i.e. no parent) if the carrier
+/// has no usable trace context, or the header is malformed: a missing or
+/// unparsable remote parent must never panic or break the request, it
+/// should simply fall back to starting a fresh trace.
+#[cfg(feature = "opentelemetry")]
+pub fn extract_trace_context<'a>(
+    carrier: &mut impl Iterator<Item = (&'a str, &'a str)>,
+    propagator: TracePropagator,
+) -> opentelemetry::Context {
+    let headers: std::collections::HashMap<&str, &str> = carrier.collect();
+
+    let span_context = match propagator {
+        TracePropagator::W3c => headers
+            .get("traceparent")
+            .and_then(|traceparent| parse_w3c_traceparent(traceparent, headers.get("tracestate").copied())),
+        TracePropagator::XRay => opentelemetry_aws_like_extract(&headers, PropagatorKind::XRay),
+        TracePropagator::Datadog => {
+            opentelemetry_aws_like_extract(&headers, PropagatorKind::Datadog)
+        }
+    };
+
+    match span_context {
+        Some(span_context) => {
+            opentelemetry::Context::new().with_remote_span_context(span_context)
+        }
+        None => opentelemetry::Context::new(),
+    }
+}
+
+#[cfg(feature = "opentelemetry")]
+fn parse_w3c_traceparent(
+    traceparent: &str,
+    tracestate: Option<&str>,
+) -> Option<opentelemetry::trace::SpanContext> {
+    use opentelemetry::trace::{SpanId, TraceFlags, TraceId, TraceState};
+
+    let parts: Vec<&str> = traceparent.split('-').collect();
+    if parts.len() < 4 {
+        return None;
+    }
+
+    let version = u8::from_str_radix(parts[0], 16).ok()?;
+    // Per the W3C spec, an unrecognized version is accepted as long as the
+    // header still has at least the minimum fields (future versions may
+    // append more); version 255 is explicitly invalid. Version 00 is
+    // final, though: the spec requires it to have *exactly* 4 fields, so a
+    // 00 header with trailing fields is malformed, not forward-compatible.
+    if version == 0xff {
+        return None;
+    }
+    if version == 0 && parts.len() != 4 {
+        return None;
+    }
+    if parts[0].len() != 2 {
+        return None;
+    }
+
+    let trace_id_hex = parts[1];
+    let span_id_hex = parts[2];
+    let flags_hex = parts[3];
+
+    if trace_id_hex.len() != 32 || span_id_hex.len() != 16 || flags_hex.len() != 2 {
+        return None;
+    }
+
+    let trace_id = TraceId::from_hex(trace_id_hex).ok()?;
+    let span_id = SpanId::from_hex(span_id_hex).ok()?;
+    let flags = u8::from_str_radix(flags_hex, 16).ok()?;
+
+    if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+        return None;
+    }
+
+    let trace_state = tracestate
+        .and_then(|value| TraceState::from_str(value).ok())
+        .unwrap_or_default();
+
+    Some(opentelemetry::trace::SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::new(flags),
+        true, // is_remote
+        trace_state,
+    ))
+}
+
+/// The non-W3C propagator formats are delegated to `opentelemetry`'s
+/// upstream crates (`opentelemetry-aws`/`opentelemetry-datadog`) rather
+/// than hand-rolled here; these two thin wrappers are the seam where that
+/// wiring happens.
+#[cfg(feature = "opentelemetry")]
+enum PropagatorKind {
+    XRay,
+    Datadog,
+}
+
+#[cfg(feature = "opentelemetry")]
+fn opentelemetry_aws_like_inject(
+    span_context: &opentelemetry::trace::SpanContext,
+    carrier: &mut impl Extend<(String, String)>,
+    kind: PropagatorKind,
+) {
+    // `XrayPropagator`/`DatadogPropagator` both implement
+    // `opentelemetry::propagation::TextMapPropagator`; we bridge to
+    // `Extend` with a small local `Injector` adapter.
+    struct ExtendInjector<'a, E>(&'a mut E);
+    impl<'a, E: Extend<(String, String)>> opentelemetry::propagation::Injector for ExtendInjector<'a, E> {
+        fn set(&mut self, key: &str, value: String) {
+            self.0.extend([(key.to_string(), value)]);
+        }
+    }
+
+    let cx = opentelemetry::Context::new().with_remote_span_context(span_context.clone());
+    let mut injector = ExtendInjector(carrier);
+    match kind {
+        PropagatorKind::XRay => {
+            opentelemetry_aws::trace::XrayPropagator::default().inject_context(&cx, &mut injector)
+        }
+        PropagatorKind::Datadog => opentelemetry_datadog::DatadogPropagator::default()
+            .inject_context(&cx, &mut injector),
+    }
+}
+
+#[cfg(feature = "opentelemetry")]
+fn opentelemetry_aws_like_extract(
+    headers: &std::collections::HashMap<&str, &str>,
+    kind: PropagatorKind,
+) -> Option<opentelemetry::trace::SpanContext> {
+    struct MapExtractor<'a>(&'a std::collections::HashMap<&'a str, &'a str>);
+    impl<'a> opentelemetry::propagation::Extractor for MapExtractor<'a> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).copied()
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().copied().collect()
+        }
+    }
+
+    let extractor = MapExtractor(headers);
+    let cx = match kind {
+        PropagatorKind::XRay => {
+            opentelemetry_aws::trace::XrayPropagator::default().extract(&extractor)
+        }
+        PropagatorKind::Datadog => {
+            opentelemetry_datadog::DatadogPropagator::default().extract(&extractor)
+        }
+    };
+    let span_context = cx.span().span_context().clone();
+    span_context.is_valid().then_some(span_context)
+}
@@ -0,0 +1,90 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exercises live filter reloading in isolation, in its own test binary:
+//! `init_with_chrome_trace_exporter` installs a *global* `tracing`
+//! subscriber, so a test that flips its filter mid-run must not share a
+//! process with any other test that also installs one (see
+//! `opentelemetry_tests.rs`).
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().expect("Lock should not be poisoned").clone())
+            .expect("Valid UTF-8")
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("Lock should not be poisoned")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().expect("Lock should not be poisoned").flush()
+    }
+}
+
+// Both scenarios below share a single test function: `tracing`'s global
+// subscriber can only be installed once per process, and `cargo test` runs
+// the tests within one binary concurrently by default, so a second
+// `init_with_chrome_trace_exporter` call in this binary would silently
+// lose the race and operate on a filter handle that was never installed.
+#[test]
+fn test_reload_handle_behavior() {
+    let buffer = SharedBuffer::new();
+
+    let (guard, filter_handle) = linera_base::tracing_opentelemetry::init_with_chrome_trace_exporter(
+        "test_reload",
+        buffer.clone(),
+    );
+
+    filter_handle
+        .set_directives("info,reload_target=off")
+        .expect("Initial directives should parse");
+
+    tracing::info_span!(target: "reload_target", "span_before_reload").in_scope(|| {
+        tracing::info!(target: "reload_target", "should not be recorded yet");
+    });
+
+    let rejected = filter_handle.set_directives("not a valid directive===");
+    assert!(
+        rejected.is_err(),
+        "Malformed directives should be rejected rather than panicking or being silently ignored"
+    );
+
+    filter_handle
+        .set_directives("info,reload_target=debug")
+        .expect("Reloaded directives should parse");
+
+    tracing::info_span!(target: "reload_target", "span_after_reload").in_scope(|| {
+        tracing::info!(target: "reload_target", "should be recorded now");
+    });
+
+    drop(guard);
+
+    let trace_json = buffer.contents();
+
+    assert!(
+        !trace_json.contains("span_before_reload"),
+        "Span created while the target was filtered off should not appear"
+    );
+    assert!(
+        trace_json.contains("span_after_reload"),
+        "Span created after raising the target's verbosity should appear"
+    );
+}
@@ -52,7 +52,7 @@ fn test_chrome_trace_includes_all_spans() {
     let buffer = SharedBuffer::new();
     let buffer_clone = buffer.clone();
 
-    let guard = linera_base::tracing_opentelemetry::init_with_chrome_trace_exporter(
+    let (guard, _filter_handle) = linera_base::tracing_opentelemetry::init_with_chrome_trace_exporter(
         "test_chrome_trace",
         buffer,
     );
@@ -92,12 +92,42 @@ fn test_chrome_trace_includes_all_spans() {
 
 #[cfg(feature = "opentelemetry")]
 #[test]
-fn test_opentelemetry_filters_skip() {
+fn test_extract_trace_context_rejects_version_zero_traceparent_with_extra_fields() {
+    use linera_base::tracing_opentelemetry::{extract_trace_context, TracePropagator};
+    use opentelemetry::trace::TraceContextExt as _;
+
+    let well_formed = [(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+    )];
+    let cx = extract_trace_context(&mut well_formed.into_iter(), TracePropagator::W3c);
+    assert!(
+        cx.span().span_context().is_valid(),
+        "A well-formed version-00 traceparent should be accepted"
+    );
+
+    let trailing_field = [(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    )];
+    let cx = extract_trace_context(&mut trailing_field.into_iter(), TracePropagator::W3c);
+    assert!(
+        !cx.span().span_context().is_valid(),
+        "Version 00 requires exactly 4 fields; a trailing field makes the header malformed, \
+         not forward-compatible, and it must be rejected rather than accepted with the extra field ignored"
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_opentelemetry_filters_skip() {
     use tracing_subscriber::{layer::SubscriberExt as _, registry::Registry};
 
     let (opentelemetry_layer, exporter, tracer_provider) =
         linera_base::tracing_opentelemetry::build_opentelemetry_layer_with_test_exporter(
             "test_opentelemetry",
+            linera_base::tracing_opentelemetry::SamplingConfig::always_on(),
+            linera_base::tracing_opentelemetry::BatchExportConfig::default(),
         );
 
     let subscriber = Registry::default().with(opentelemetry_layer);
@@ -144,3 +174,365 @@ fn test_opentelemetry_filters_skip() {
         span_names
     );
 }
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_opentelemetry_span_kind_and_status() {
+    use opentelemetry::trace::{SpanKind, Status};
+    use tracing_subscriber::{layer::SubscriberExt as _, registry::Registry};
+
+    #[instrument(fields(otel.kind = "server"))]
+    fn handle_inbound_request() {
+        tracing::info!("handling request");
+    }
+
+    #[instrument(fields(otel.kind = "client"))]
+    fn send_outbound_request() {
+        tracing::info!("sending request");
+    }
+
+    #[instrument(fields(otel.status_code = "error", otel.status_message = "boom"))]
+    fn failing_operation() {
+        tracing::error!("it broke");
+    }
+
+    let (opentelemetry_layer, exporter, tracer_provider) =
+        linera_base::tracing_opentelemetry::build_opentelemetry_layer_with_test_exporter(
+            "test_opentelemetry_kind",
+            linera_base::tracing_opentelemetry::SamplingConfig::always_on(),
+            linera_base::tracing_opentelemetry::BatchExportConfig::default(),
+        );
+
+    let subscriber = Registry::default().with(opentelemetry_layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        handle_inbound_request();
+        send_outbound_request();
+        failing_operation();
+
+        let internal_span = info_span!("plain_internal_span").entered();
+        drop(internal_span);
+    });
+
+    drop(tracer_provider);
+
+    let exported_spans = exporter
+        .get_finished_spans()
+        .expect("Failed to get exported spans");
+
+    let find = |name: &str| {
+        exported_spans
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("Span {name:?} was not exported"))
+    };
+
+    assert_eq!(find("handle_inbound_request").span_kind, SpanKind::Server);
+    assert_eq!(find("send_outbound_request").span_kind, SpanKind::Client);
+    assert_eq!(
+        find("plain_internal_span").span_kind,
+        SpanKind::Internal,
+        "Spans without an otel.kind field should default to Internal"
+    );
+    assert_eq!(
+        find("failing_operation").status,
+        Status::error("boom"),
+        "otel.status_code/otel.status_message should mark the span as errored"
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_sampling_keeps_whole_traces_and_honors_overrides() {
+    use linera_base::tracing_opentelemetry::SamplingConfig;
+    use tracing_subscriber::{layer::SubscriberExt as _, registry::Registry};
+
+    #[instrument]
+    fn dropped_root() {
+        let _child = info_span!("dropped_child").entered();
+        tracing::info!("inside a dropped trace");
+    }
+
+    #[instrument]
+    fn always_kept_root() {
+        let _child = info_span!("always_kept_child").entered();
+        tracing::info!("inside an always-kept trace");
+    }
+
+    let sampling = SamplingConfig::new(0.0).with_target_ratio("always_kept_root", 1.0);
+
+    let (opentelemetry_layer, exporter, tracer_provider) =
+        linera_base::tracing_opentelemetry::build_opentelemetry_layer_with_test_exporter(
+            "test_sampling",
+            sampling,
+            linera_base::tracing_opentelemetry::BatchExportConfig::default(),
+        );
+
+    let subscriber = Registry::default().with(opentelemetry_layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        dropped_root();
+        always_kept_root();
+    });
+
+    drop(tracer_provider);
+
+    let exported_spans = exporter
+        .get_finished_spans()
+        .expect("Failed to get exported spans");
+    let span_names: Vec<String> = exported_spans.iter().map(|s| s.name.to_string()).collect();
+
+    assert!(
+        !span_names.contains(&"dropped_root".to_string())
+            && !span_names.contains(&"dropped_child".to_string()),
+        "A trace sampled at ratio 0.0 should be dropped in its entirety. Found spans: {:?}",
+        span_names
+    );
+    assert!(
+        span_names.contains(&"always_kept_root".to_string())
+            && span_names.contains(&"always_kept_child".to_string()),
+        "A target overridden to ratio 1.0 should keep its whole trace. Found spans: {:?}",
+        span_names
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_spans_sharing_a_scope_group_into_a_single_batch() {
+    use linera_base::tracing_opentelemetry::{
+        group_spans_by_resource_and_scope, BatchExportConfig, SamplingConfig,
+    };
+    use tracing_subscriber::{layer::SubscriberExt as _, registry::Registry};
+
+    const SPAN_COUNT: usize = 20;
+
+    let (opentelemetry_layer, exporter, tracer_provider) =
+        linera_base::tracing_opentelemetry::build_opentelemetry_layer_with_test_exporter(
+            "test_grouping",
+            SamplingConfig::always_on(),
+            BatchExportConfig::default(),
+        );
+
+    let subscriber = Registry::default().with(opentelemetry_layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..SPAN_COUNT {
+            info_span!("grouped_span", index = i).in_scope(|| {
+                tracing::info!("part of a shared-scope batch");
+            });
+        }
+    });
+
+    drop(tracer_provider);
+
+    let exported_spans = exporter
+        .get_finished_spans()
+        .expect("Failed to get exported spans");
+    assert_eq!(exported_spans.len(), SPAN_COUNT);
+
+    let resource = opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+        "service.name",
+        "test_grouping",
+    )]);
+    let groups = group_spans_by_resource_and_scope(&resource, &exported_spans);
+
+    assert_eq!(
+        groups.len(),
+        1,
+        "All spans share one tracer, so they should collapse into a single ScopeSpans group"
+    );
+    assert_eq!(
+        groups[0].spans.len(),
+        SPAN_COUNT,
+        "The single group should contain every span, not just the first"
+    );
+    assert_eq!(
+        groups[0]
+            .resource
+            .get(opentelemetry::Key::new("service.name"))
+            .as_ref()
+            .map(opentelemetry::Value::as_str),
+        Some(std::borrow::Cow::Borrowed("test_grouping")),
+        "Each group should carry the resource it was exported under"
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_http_json_transport_round_trips_resource_and_span_name() {
+    use linera_base::tracing_opentelemetry::{
+        BatchExportConfig, OtlpExporterConfig, OtlpTransport, SamplingConfig,
+    };
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/traces"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    let config = OtlpExporterConfig {
+        transport: OtlpTransport::HttpJson,
+        endpoint: Some(format!("{}/v1/traces", mock_server.uri())),
+        ..Default::default()
+    };
+
+    let (guard, _filter_handle) = linera_base::tracing_opentelemetry::init_with_otlp_exporter(
+        "test_http_json",
+        config,
+        SamplingConfig::always_on(),
+        BatchExportConfig {
+            max_export_batch_size: 1,
+            scheduled_delay: std::time::Duration::from_millis(20),
+        },
+    )
+    .expect("OTLP pipeline should initialize against the mock collector");
+
+    tracing::info_span!("http_json_round_trip_span").in_scope(|| {
+        tracing::info!("hello from the HTTP/JSON transport");
+    });
+
+    drop(guard);
+    // The batch processor's background flush may still be in flight right
+    // after the guard's shutdown call returns; give it a moment to land.
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let requests = mock_server.received_requests().await.expect("Mock server should have recorded requests");
+    assert!(
+        !requests.is_empty(),
+        "The HTTP/JSON exporter should have POSTed at least one OTLP request"
+    );
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[0].body).expect("HTTP/JSON transport body should be valid JSON");
+
+    let resource_spans = body["resourceSpans"]
+        .as_array()
+        .expect("Payload should have a resourceSpans array");
+
+    let service_name_present = resource_spans.iter().any(|resource_spans_entry| {
+        resource_spans_entry["resource"]["attributes"]
+            .as_array()
+            .is_some_and(|attributes| {
+                attributes.iter().any(|attribute| {
+                    attribute["key"] == "service.name"
+                        && attribute["value"]["stringValue"] == "test_http_json"
+                })
+            })
+    });
+    assert!(
+        service_name_present,
+        "Exported resource should carry the configured service name. Payload: {body}"
+    );
+
+    let span_name_present = resource_spans.iter().any(|resource_spans_entry| {
+        resource_spans_entry["scopeSpans"]
+            .as_array()
+            .is_some_and(|scope_spans| {
+                scope_spans.iter().any(|scope_spans_entry| {
+                    scope_spans_entry["spans"]
+                        .as_array()
+                        .is_some_and(|spans| {
+                            spans
+                                .iter()
+                                .any(|span| span["name"] == "http_json_round_trip_span")
+                        })
+                })
+            })
+    });
+    assert!(
+        span_name_present,
+        "Exported span name should appear in the JSON payload. Payload: {body}"
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[tokio::test]
+async fn test_http_json_transport_groups_spans_sharing_a_scope_into_one_scope_spans_entry() {
+    use linera_base::tracing_opentelemetry::{
+        BatchExportConfig, OtlpExporterConfig, OtlpTransport, SamplingConfig,
+    };
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const SPAN_COUNT: usize = 5;
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/traces"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    let config = OtlpExporterConfig {
+        transport: OtlpTransport::HttpJson,
+        endpoint: Some(format!("{}/v1/traces", mock_server.uri())),
+        ..Default::default()
+    };
+
+    // A batch size big enough to hold every span lets one POST carry all of
+    // them, so the assertions below exercise how the encoder lays out a
+    // multi-span batch rather than how many requests get sent.
+    let (guard, _filter_handle) = linera_base::tracing_opentelemetry::init_with_otlp_exporter(
+        "test_http_json_grouping",
+        config,
+        SamplingConfig::always_on(),
+        BatchExportConfig {
+            max_export_batch_size: SPAN_COUNT,
+            scheduled_delay: std::time::Duration::from_millis(20),
+        },
+    )
+    .expect("OTLP pipeline should initialize against the mock collector");
+
+    for i in 0..SPAN_COUNT {
+        tracing::info_span!("grouped_http_json_span", index = i).in_scope(|| {
+            tracing::info!("part of a shared-scope batch");
+        });
+    }
+
+    drop(guard);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let requests = mock_server.received_requests().await.expect("Mock server should have recorded requests");
+    assert!(
+        !requests.is_empty(),
+        "The HTTP/JSON exporter should have POSTed at least one OTLP request"
+    );
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests[0].body).expect("HTTP/JSON transport body should be valid JSON");
+
+    let resource_spans = body["resourceSpans"]
+        .as_array()
+        .expect("Payload should have a resourceSpans array");
+    assert_eq!(
+        resource_spans.len(),
+        1,
+        "All spans share one resource, so the payload should have a single ResourceSpans entry. Payload: {body}"
+    );
+
+    let scope_spans = resource_spans[0]["scopeSpans"]
+        .as_array()
+        .expect("ResourceSpans entry should have a scopeSpans array");
+    assert_eq!(
+        scope_spans.len(),
+        1,
+        "All spans share one tracer, so they should collapse into a single ScopeSpans entry on the wire, \
+         not be repeated once per span. Payload: {body}"
+    );
+
+    let spans = scope_spans[0]["spans"]
+        .as_array()
+        .expect("ScopeSpans entry should have a spans array");
+    assert_eq!(
+        spans.len(),
+        SPAN_COUNT,
+        "The single ScopeSpans entry should carry every span in the batch. Payload: {body}"
+    );
+}